@@ -0,0 +1,156 @@
+//! Helpers for constructing and running `std::process::Command`s.
+//!
+//! Every subprocess topgrade spawns should go through [`create_command`] rather than
+//! `std::process::Command::new` directly. On Windows, `Command::new("foo")` lets the OS loader
+//! search the current working directory before `PATH`, so a binary dropped into whatever
+//! directory topgrade happens to be invoked from can shadow the real executable. `create_command`
+//! resolves the name to an absolute path itself, walking `PATH` (and, on Windows, each
+//! `PATHEXT` suffix) while explicitly skipping the cwd.
+
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, ExitStatus};
+
+use color_eyre::eyre::{eyre, Result};
+
+/// Resolve `name` to an absolute path via `PATH` (and `PATHEXT` on Windows) and build a
+/// [`Command`] for it.
+///
+/// If `name` already contains a path separator it is used as-is without a `PATH` search,
+/// matching the behavior of a shell when given a path rather than a bare command name; an error
+/// is returned if that path doesn't exist. Otherwise the current working directory is never consulted -- only `PATH` entries are
+/// searched, in order, so a same-named file sitting in the cwd cannot shadow the real binary.
+///
+/// Returns an error rather than falling back to the unresolved bare name when resolution fails --
+/// silently handing back a `Command::new(name)` here would reopen the exact cwd-shadowing hole
+/// this function exists to close.
+pub fn create_command<S: AsRef<OsStr>>(name: S) -> Result<Command> {
+    let name = name.as_ref();
+
+    let path = resolve_executable(name).ok_or_else(|| eyre!("{:?} not found on PATH", name))?;
+
+    #[allow(clippy::disallowed_methods)]
+    Ok(Command::new(path))
+}
+
+fn resolve_executable(name: &OsStr) -> Option<PathBuf> {
+    let name_path = Path::new(name);
+
+    // A name with a path separator (relative or absolute) bypasses PATH search entirely --
+    // there is nothing to disambiguate from the cwd.
+    if name_path.components().count() > 1 {
+        return name_path.exists().then(|| name_path.to_path_buf());
+    }
+
+    let path_var = env::var_os("PATH")?;
+
+    for dir in env::split_paths(&path_var) {
+        for candidate in candidates(&dir, name_path) {
+            if is_executable_file(&candidate) {
+                return Some(candidate);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(windows)]
+fn is_executable_file(path: &Path) -> bool {
+    path.is_file()
+}
+
+// `execvp`'s PATH search (what plain `Command::new` relies on) skips entries that exist but
+// aren't executable -- e.g. `EACCES` -- and keeps looking at later `PATH` entries. Matching that
+// here means a non-executable file earlier on `PATH` doesn't shadow an executable one later on.
+#[cfg(not(windows))]
+fn is_executable_file(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.is_file()
+        && std::fs::metadata(path)
+            .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn candidates(dir: &Path, name: &Path) -> Vec<PathBuf> {
+    // If the name already carries an extension that Windows considers executable, try it
+    // verbatim first.
+    let base = dir.join(name);
+    let mut out = vec![base.clone()];
+
+    let exts = env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".to_string());
+    for ext in exts.split(';').filter(|e| !e.is_empty()) {
+        let mut with_ext = base.clone();
+        let mut file_name = with_ext.file_name().unwrap_or_default().to_os_string();
+        file_name.push(ext);
+        with_ext.set_file_name(file_name);
+        out.push(with_ext);
+    }
+
+    out
+}
+
+#[cfg(not(windows))]
+fn candidates(dir: &Path, name: &Path) -> Vec<PathBuf> {
+    vec![dir.join(name)]
+}
+
+/// Extension methods shared by every [`Command`] topgrade runs, mirroring the checks topgrade
+/// wants applied uniformly (propagating failures as [`color_eyre::Report`]s instead of bare
+/// `ExitStatus`es).
+pub trait CommandExt {
+    /// Run the command and return an error unless it exits successfully.
+    fn status_checked(&mut self) -> Result<()>;
+
+    /// Run the command and return an error unless it exits successfully or with one of `codes`.
+    fn status_checked_with_codes(&mut self, codes: &[i32]) -> Result<()>;
+
+    /// Run the command, capture its output, and return an error unless it exits successfully.
+    fn output_checked(&mut self) -> Result<Output>;
+
+    /// Like [`CommandExt::output_checked`] but returns stdout and stderr decoded (lossily) as
+    /// UTF-8.
+    fn output_checked_utf8(&mut self) -> Result<Utf8Output>;
+}
+
+/// The UTF-8-decoded stdout/stderr of a command that exited successfully.
+pub struct Utf8Output {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandExt for Command {
+    fn status_checked(&mut self) -> Result<()> {
+        self.status_checked_with_codes(&[])
+    }
+
+    fn status_checked_with_codes(&mut self, codes: &[i32]) -> Result<()> {
+        let status = self.status()?;
+        check_status(&self, status, codes)
+    }
+
+    fn output_checked(&mut self) -> Result<Output> {
+        let output = self.output()?;
+        check_status(&self, output.status, &[])?;
+        Ok(output)
+    }
+
+    fn output_checked_utf8(&mut self) -> Result<Utf8Output> {
+        let output = self.output_checked()?;
+        Ok(Utf8Output {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+fn check_status(command: &Command, status: ExitStatus, codes: &[i32]) -> Result<()> {
+    if status.success() || status.code().is_some_and(|c| codes.contains(&c)) {
+        return Ok(());
+    }
+
+    Err(eyre!("{:?} failed with {}", command, status))
+}
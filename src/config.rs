@@ -0,0 +1,38 @@
+//! The `[zsh]` section of topgrade's config file.
+//!
+//! This only covers the slice of `Config` that the zsh step needs; the rest of `Config` (steps'
+//! other sections, global flags, etc.) lives alongside this in the full config file and isn't
+//! reproduced here.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::steps::zsh::CustomZshFramework;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Zsh {
+    /// Plugin managers topgrade doesn't know about out of the box, declared by name, e.g.:
+    ///
+    /// ```toml
+    /// [zsh.custom_frameworks.sheldon]
+    /// env-var = "SHELDON_DATA_DIR"
+    /// default-rel-path = ".local/share/sheldon"
+    /// commands = ["sheldon lock --update"]
+    /// ```
+    #[serde(default)]
+    custom_frameworks: HashMap<String, CustomZshFramework>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    zsh: Zsh,
+}
+
+impl Config {
+    /// User-declared zsh frameworks from `[zsh.custom_frameworks]`.
+    pub fn zsh_custom_frameworks(&self) -> &HashMap<String, CustomZshFramework> {
+        &self.zsh.custom_frameworks
+    }
+}
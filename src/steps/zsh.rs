@@ -1,155 +1,391 @@
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::Stdio;
+use std::sync::{Mutex, OnceLock};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 use directories::BaseDirs;
+use serde::Deserialize;
 use tracing::debug;
 use walkdir::WalkDir;
 
-use crate::command::CommandExt;
+use crate::command::{create_command, CommandExt};
 use crate::execution_context::ExecutionContext;
-use crate::executor::RunType;
 use crate::git::Repositories;
 use crate::terminal::print_separator;
 use crate::utils::{require, PathExt};
 
-pub fn run_zr(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
+/// Read `name` from the user's real zsh environment rather than trusting `std::env::var`, which
+/// only sees what's in topgrade's own process environment. Frameworks' env vars are commonly set
+/// in `/etc/zsh/zshenv` or the user's login shell rather than there, so this launches a login,
+/// interactive zsh (`zsh -l -i -c`, which sources `.zshenv`, `.zprofile` and `.zshrc`) and asks
+/// it directly, only falling back to `fallback` if the process environment
+/// doesn't have it either and the zsh query comes back empty or fails. Results (including
+/// fallbacks) are memoized per `name` for the lifetime of the process, since a single run can
+/// otherwise query the same handful of variables once per enabled framework.
+fn zsh_env_var(name: &str, fallback: PathBuf) -> PathBuf {
+    if let Ok(value) = env::var(name) {
+        if !value.is_empty() {
+            return PathBuf::from(value);
+        }
+    }
+
+    static CACHE: OnceLock<Mutex<HashMap<String, PathBuf>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
 
-    require("zr")?;
+    if let Some(cached) = cache.lock().unwrap().get(name) {
+        return cached.clone();
+    }
 
-    print_separator("zr");
+    let resolved = create_command("zsh")
+        .ok()
+        .and_then(|mut cmd| {
+            cmd.args(["-l", "-i", "-c", &format!("print -n ${name}")])
+                // Never let a startup file's prompt (ssh-agent, keychain, ...) block on input.
+                .stdin(Stdio::null())
+                .output_checked_utf8()
+                .ok()
+        })
+        .map(|o| o.stdout)
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or(fallback);
 
-    let cmd = format!("source {} && zr --update", zshrc(base_dirs).display());
-    run_type.execute(zsh).args(["-l", "-c", cmd.as_str()]).status_checked()
+    cache.lock().unwrap().insert(name.to_string(), resolved.clone());
+    resolved
 }
 
 fn zdotdir(base_dirs: &BaseDirs) -> PathBuf {
-    env::var("ZDOTDIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().to_path_buf())
+    zsh_env_var("ZDOTDIR", base_dirs.home_dir().to_path_buf())
 }
 
 pub fn zshrc(base_dirs: &BaseDirs) -> PathBuf {
     zdotdir(base_dirs).join(".zshrc")
 }
 
-pub fn run_antidote(ctx: &ExecutionContext) -> Result<()> {
-    let zsh = require("zsh")?;
-    let mut antidote = zdotdir(ctx.base_dirs()).join(".antidote").require()?;
-    antidote.push("antidote.zsh");
-
-    print_separator("antidote");
-
-    ctx.run_type()
-        .execute(zsh)
-        .arg("-c")
-        .arg(format!("source {} && antidote update", antidote.display()))
-        .status_checked()
+/// Where a framework's install directory lives when its `env_var` isn't set.
+#[derive(Debug, Clone, Copy)]
+enum SentinelRoot {
+    /// Relative to `$HOME` -- the convention most plugin managers use.
+    Home,
+    /// Relative to `$ZDOTDIR` (falling back to `$HOME`) -- for frameworks that live
+    /// alongside the user's zsh config rather than directly in their home directory.
+    ZshDotDir,
 }
 
-pub fn run_antibody(run_type: RunType) -> Result<()> {
-    require("zsh")?;
-    let antibody = require("antibody")?;
-
-    print_separator("antibody");
-
-    run_type.execute(antibody).arg("update").status_checked()
+/// A zsh plugin manager/framework that topgrade knows how to update.
+///
+/// All the built-in frameworks (`zr`, antidote, antibody, antigen, zgenom, zplug, zinit, zi,
+/// zim) boil down to this same shape: make sure `zsh` (and maybe a companion binary) is on
+/// `PATH`, make sure the framework is actually installed by checking a sentinel path, then run
+/// one or more update commands -- either under a `zsh -c` of some flavor (optionally requiring
+/// and/or sourcing `.zshrc` first) or, for frameworks whose update command is its own binary, by
+/// running that binary directly. Users can declare additional frameworks of their own under
+/// `[zsh.custom_frameworks]` without needing to patch this file.
+struct ZshFramework {
+    name: String,
+    binary: Option<String>,
+    env_var: Option<String>,
+    default_rel_path: Option<String>,
+    sentinel_root: SentinelRoot,
+    /// Extra flag for the `zsh` invocation, e.g. `Some("-i")`/`Some("-l")`, or `None` for a
+    /// plain `zsh -c`.
+    shell_flag: Option<String>,
+    /// Whether `.zshrc` must exist before running (independent of whether it's sourced --
+    /// interactive/login shells source it on their own).
+    require_zshrc: bool,
+    /// Whether to prepend `source <zshrc> &&` to the update command.
+    source_zshrc: bool,
+    source_sentinel: bool,
+    commands: Vec<String>,
+    success_codes: Vec<i32>,
 }
 
-pub fn run_antigen(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    let zshrc = zshrc(base_dirs).require()?;
-    env::var("ADOTDIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().join("antigen.zsh"))
-        .require()?;
-
-    print_separator("antigen");
+impl ZshFramework {
+    fn sentinel(&self, base_dirs: &BaseDirs) -> Option<PathBuf> {
+        if self.env_var.is_none() && self.default_rel_path.is_none() {
+            return None;
+        }
+
+        let root = match self.sentinel_root {
+            SentinelRoot::Home => base_dirs.home_dir().to_path_buf(),
+            SentinelRoot::ZshDotDir => zdotdir(base_dirs),
+        };
+        let default = root.join(self.default_rel_path.as_deref().unwrap_or_default());
+
+        Some(match &self.env_var {
+            Some(var) => zsh_env_var(var, default),
+            None => default,
+        })
+    }
 
-    let cmd = format!("source {} && (antigen selfupdate ; antigen update)", zshrc.display());
-    run_type.execute(zsh).args(["-l", "-c", cmd.as_str()]).status_checked()
+    fn run(&self, ctx: &ExecutionContext) -> Result<()> {
+        let zsh = require("zsh")?;
+        let binary = self.binary.as_deref().map(require).transpose()?;
+
+        let sentinel = self.sentinel(ctx.base_dirs());
+        if let Some(path) = &sentinel {
+            path.clone().require()?;
+        }
+
+        print_separator(&self.name);
+
+        // Frameworks with no install-directory sentinel and nothing to source run their own
+        // update binary directly instead of going through a zsh shell (e.g. antibody).
+        if sentinel.is_none() && !self.source_zshrc {
+            let binary = binary.ok_or_else(|| {
+                eyre!(
+                    "zsh framework '{}' has no sentinel path, doesn't source .zshrc, and declares no binary -- \
+                     nothing tells it how to run (set `env-var`/`default-rel-path`, `source-zshrc`, or `binary`)",
+                    self.name
+                )
+            })?;
+            return ctx
+                .run_type()
+                .execute(binary)
+                .args(self.commands.iter().flat_map(|c| c.split_whitespace()))
+                .status_checked_with_codes(&self.success_codes);
+        }
+
+        if self.require_zshrc {
+            zshrc(ctx.base_dirs()).require()?;
+        }
+
+        let mut script = String::new();
+        if self.source_zshrc {
+            script.push_str(&format!("source {} && ", zshrc(ctx.base_dirs()).display()));
+        } else if self.source_sentinel {
+            let sentinel = sentinel.as_ref().expect("source_sentinel requires a sentinel path");
+            script.push_str(&format!("source {} && ", sentinel.display()));
+        }
+        script.push_str(&self.commands.join(" && "));
+
+        let mut command = ctx.run_type().execute(zsh);
+        if let Some(flag) = &self.shell_flag {
+            command.arg(flag);
+        }
+        command.args(["-c", script.as_str()]).status_checked_with_codes(&self.success_codes)
+    }
 }
 
-pub fn run_zgenom(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    let zshrc = zshrc(base_dirs).require()?;
-    env::var("ZGEN_SOURCE")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().join(".zgenom"))
-        .require()?;
-
-    print_separator("zgenom");
-
-    let cmd = format!("source {} && zgenom selfupdate && zgenom update", zshrc.display());
-    run_type.execute(zsh).args(["-l", "-c", cmd.as_str()]).status_checked()
+fn builtin_frameworks() -> Vec<ZshFramework> {
+    vec![
+        ZshFramework {
+            name: "zr".to_string(),
+            binary: Some("zr".to_string()),
+            env_var: None,
+            default_rel_path: None,
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-l".to_string()),
+            require_zshrc: false,
+            source_zshrc: true,
+            source_sentinel: false,
+            commands: vec!["zr --update".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "antidote".to_string(),
+            binary: None,
+            env_var: None,
+            default_rel_path: Some(".antidote/antidote.zsh".to_string()),
+            sentinel_root: SentinelRoot::ZshDotDir,
+            shell_flag: None,
+            require_zshrc: false,
+            source_zshrc: false,
+            source_sentinel: true,
+            commands: vec!["antidote update".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "antibody".to_string(),
+            binary: Some("antibody".to_string()),
+            env_var: None,
+            default_rel_path: None,
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: None,
+            require_zshrc: false,
+            source_zshrc: false,
+            source_sentinel: false,
+            commands: vec!["update".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "antigen".to_string(),
+            binary: None,
+            env_var: Some("ADOTDIR".to_string()),
+            default_rel_path: Some("antigen.zsh".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-l".to_string()),
+            require_zshrc: true,
+            source_zshrc: true,
+            source_sentinel: false,
+            commands: vec!["(antigen selfupdate ; antigen update)".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "zgenom".to_string(),
+            binary: None,
+            env_var: Some("ZGEN_SOURCE".to_string()),
+            default_rel_path: Some(".zgenom".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-l".to_string()),
+            require_zshrc: true,
+            source_zshrc: true,
+            source_sentinel: false,
+            commands: vec!["zgenom selfupdate".to_string(), "zgenom update".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "zplug".to_string(),
+            binary: None,
+            env_var: Some("ZPLUG_HOME".to_string()),
+            default_rel_path: Some(".zplug".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-i".to_string()),
+            require_zshrc: true,
+            // Like zim, and unlike the other frameworks, zplug's update command never sourced .zshrc itself --
+            // it ran under `-i`, which has the interactive shell source it on its own, so doing
+            // it again here would double-run startup hooks/aliases.
+            source_zshrc: false,
+            source_sentinel: false,
+            commands: vec!["zplug update".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "zinit".to_string(),
+            binary: None,
+            env_var: Some("ZINIT_HOME".to_string()),
+            default_rel_path: Some(".zinit".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-i".to_string()),
+            require_zshrc: true,
+            source_zshrc: true,
+            source_sentinel: false,
+            commands: vec!["zinit self-update".to_string(), "zinit update --all".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "zi".to_string(),
+            binary: None,
+            env_var: None,
+            default_rel_path: Some(".zi".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-i".to_string()),
+            require_zshrc: true,
+            source_zshrc: true,
+            source_sentinel: false,
+            commands: vec!["zi self-update".to_string(), "zi update --all".to_string()],
+            success_codes: vec![],
+        },
+        ZshFramework {
+            name: "zim".to_string(),
+            binary: None,
+            env_var: Some("ZIM_HOME".to_string()),
+            default_rel_path: Some(".zim".to_string()),
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some("-i".to_string()),
+            require_zshrc: false,
+            source_zshrc: false,
+            source_sentinel: false,
+            commands: vec!["zimfw upgrade".to_string(), "zimfw update".to_string()],
+            success_codes: vec![],
+        },
+    ]
 }
 
-pub fn run_zplug(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    zshrc(base_dirs).require()?;
+fn framework(name: &str) -> ZshFramework {
+    builtin_frameworks()
+        .into_iter()
+        .find(|f| f.name == name)
+        .unwrap_or_else(|| panic!("no built-in zsh framework named {name}"))
+}
 
-    env::var("ZPLUG_HOME")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().join(".zplug"))
-        .require()?;
+pub fn run_zr(ctx: &ExecutionContext) -> Result<()> {
+    framework("zr").run(ctx)
+}
 
-    print_separator("zplug");
+pub fn run_antidote(ctx: &ExecutionContext) -> Result<()> {
+    framework("antidote").run(ctx)
+}
 
-    run_type
-        .execute(zsh)
-        .args(["-i", "-c", "zplug update"])
-        .status_checked()
+pub fn run_antibody(ctx: &ExecutionContext) -> Result<()> {
+    framework("antibody").run(ctx)
 }
 
-pub fn run_zinit(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    let zshrc = zshrc(base_dirs).require()?;
+pub fn run_antigen(ctx: &ExecutionContext) -> Result<()> {
+    framework("antigen").run(ctx)
+}
 
-    env::var("ZINIT_HOME")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().join(".zinit"))
-        .require()?;
+pub fn run_zgenom(ctx: &ExecutionContext) -> Result<()> {
+    framework("zgenom").run(ctx)
+}
 
-    print_separator("zinit");
+pub fn run_zplug(ctx: &ExecutionContext) -> Result<()> {
+    framework("zplug").run(ctx)
+}
 
-    let cmd = format!("source {} && zinit self-update && zinit update --all", zshrc.display(),);
-    run_type.execute(zsh).args(["-i", "-c", cmd.as_str()]).status_checked()
+pub fn run_zinit(ctx: &ExecutionContext) -> Result<()> {
+    framework("zinit").run(ctx)
 }
 
-pub fn run_zi(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    let zshrc = zshrc(base_dirs).require()?;
+pub fn run_zi(ctx: &ExecutionContext) -> Result<()> {
+    framework("zi").run(ctx)
+}
 
-    base_dirs.home_dir().join(".zi").require()?;
+pub fn run_zim(ctx: &ExecutionContext) -> Result<()> {
+    framework("zim").run(ctx)
+}
 
-    print_separator("zi");
+/// A user-declared entry under `[zsh.custom_frameworks]`, for plugin managers topgrade doesn't
+/// know about out of the box (sheldon, znap, zpm, ...).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CustomZshFramework {
+    /// Extra binary (besides `zsh`) required to be on `PATH`, if any.
+    binary: Option<String>,
+    /// Environment variable pointing at the framework's install directory.
+    env_var: Option<String>,
+    /// Home-relative path used when `env_var` is unset or empty.
+    default_rel_path: Option<String>,
+    #[serde(default)]
+    interactive: bool,
+    #[serde(default = "default_source_zshrc")]
+    source_zshrc: bool,
+    commands: Vec<String>,
+    #[serde(default)]
+    success_codes: Vec<i32>,
+}
 
-    let cmd = format!("source {} && zi self-update && zi update --all", zshrc.display(),);
-    run_type.execute(zsh).args(["-i", "-c", &cmd]).status_checked()
+fn default_source_zshrc() -> bool {
+    true
 }
 
-pub fn run_zim(base_dirs: &BaseDirs, run_type: RunType) -> Result<()> {
-    let zsh = require("zsh")?;
-    env::var("ZIM_HOME")
-        .or_else(|_| {
-            Command::new("zsh")
-                // TODO: Should these be quoted?
-                .args(["-c", "[[ -n ${ZIM_HOME} ]] && print -n ${ZIM_HOME}"])
-                .output_checked_utf8()
-                .map(|o| o.stdout)
-        })
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| base_dirs.home_dir().join(".zim"))
-        .require()?;
+impl CustomZshFramework {
+    fn into_framework(self, name: String) -> ZshFramework {
+        ZshFramework {
+            name,
+            binary: self.binary,
+            env_var: self.env_var,
+            default_rel_path: self.default_rel_path,
+            sentinel_root: SentinelRoot::Home,
+            shell_flag: Some(if self.interactive { "-i" } else { "-l" }.to_string()),
+            require_zshrc: self.source_zshrc,
+            source_zshrc: self.source_zshrc,
+            source_sentinel: false,
+            commands: self.commands,
+            success_codes: self.success_codes,
+        }
+    }
+}
 
-    print_separator("zim");
+/// Run every plugin manager the user declared under `[zsh.custom_frameworks]`.
+pub fn run_custom_frameworks(ctx: &ExecutionContext) -> Result<()> {
+    for (name, custom) in ctx.config().zsh_custom_frameworks() {
+        custom.clone().into_framework(name.clone()).run(ctx)?;
+    }
 
-    run_type
-        .execute(zsh)
-        .args(["-i", "-c", "zimfw upgrade && zimfw update"])
-        .status_checked()
+    Ok(())
 }
 
 pub fn run_oh_my_zsh(ctx: &ExecutionContext) -> Result<()> {
@@ -158,24 +394,7 @@ pub fn run_oh_my_zsh(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("oh-my-zsh");
 
-    let custom_dir = env::var::<_>("ZSH_CUSTOM")
-        .or_else(|_| {
-            Command::new("zsh")
-                // TODO: Should these be quoted?
-                .args(["-c", "test $ZSH_CUSTOM && echo -n $ZSH_CUSTOM"])
-                .output_checked_utf8()
-                .map(|o| o.stdout)
-        })
-        .map(PathBuf::from)
-        .unwrap_or_else(|e| {
-            let default_path = oh_my_zsh.join("custom");
-            debug!(
-                "Running zsh returned {}. Using default path: {}",
-                e,
-                default_path.display()
-            );
-            default_path
-        });
+    let custom_dir = zsh_env_var("ZSH_CUSTOM", oh_my_zsh.join("custom"));
 
     debug!("oh-my-zsh custom dir: {}", custom_dir.display());
 
@@ -0,0 +1,37 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::command::create_command;
+
+/// Look up `name` on `PATH`, returning its resolved absolute path.
+///
+/// This goes through [`create_command`]'s resolution logic (rather than just checking
+/// `which`-style presence) so that callers who `.require()` a binary and then hand its name to
+/// [`crate::executor::RunType::execute`] get the same cwd-excluding, `PATHEXT`-aware lookup that
+/// `execute` itself performs.
+pub fn require(name: &str) -> Result<PathBuf> {
+    let command = create_command(name).map_err(|_| eyre!("{name} is not installed"))?;
+    Ok(PathBuf::from(command.get_program()))
+}
+
+pub trait PathExt {
+    /// Return `self` if the path exists, otherwise an error.
+    fn require(self) -> Result<PathBuf>;
+}
+
+impl PathExt for PathBuf {
+    fn require(self) -> Result<PathBuf> {
+        if self.exists() {
+            Ok(self)
+        } else {
+            Err(eyre!("{} doesn't exist", self.display()))
+        }
+    }
+}
+
+impl PathExt for &Path {
+    fn require(self) -> Result<PathBuf> {
+        self.to_path_buf().require()
+    }
+}